@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Loaded from `filters.toml` at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    pub blocklist_hosts: Vec<String>,
+    /// Regexes matched against the full URL.
+    pub blocklist_patterns: Vec<String>,
+    /// Always eligible for (re-)queueing, bypassing the refetch age gate.
+    pub allowlist_hosts: Vec<String>,
+    pub max_refetch_age_secs: u64,
+    /// `0` disables degree-based pruning.
+    pub min_in_degree: u32,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            blocklist_hosts: vec!["youtube.com".to_string()],
+            blocklist_patterns: Vec::new(),
+            allowlist_hosts: Vec::new(),
+            max_refetch_age_secs: 60 * 60 * 24 * 7,
+            min_in_degree: 0,
+        }
+    }
+}
+
+impl Filters {
+    /// Falls back to `Filters::default()` if missing or malformed.
+    pub fn load() -> Self {
+        std::fs::read_to_string("filters.toml")
+            .ok()
+            .and_then(|text| toml::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+}
@@ -1,16 +1,89 @@
+use crate::filters::Filters;
 use crate::types::{DomainInfo, Graph};
+use futures::future::{select_all, BoxFuture};
 use rand::prelude::SliceRandom;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+const GRAPH_PATH: &str = "graph.json";
+const GRAPH_BAK_PATH: &str = "graph.bak.json";
+const JOURNAL_PATH: &str = "graph.journal.jsonl";
+const CHANGES_PATH: &str = "graph.changes.patch";
+
+/// Number of journal records to accumulate before materializing a fresh
+/// `graph.json` snapshot and truncating the journal.
+const SNAPSHOT_INTERVAL: usize = 500;
 
 pub struct Manager {
     pub queue: Vec<String>,
     pub graph: Graph,
+    journal_len: usize,
+    filters: Filters,
+    blocklist_patterns: Vec<Regex>,
+    track_changes: bool,
+    /// The original seed URLs passed to `Manager::new`, kept around
+    /// separately from `queue` (which drains as URLs are dequeued) so seeds
+    /// stay exempt from degree-based pruning even after their first crawl.
+    seeds: HashSet<String>,
 }
 
+/// A single mutation appended to `graph.journal.jsonl`. Replaying these in
+/// order against the last snapshot reconstructs the current graph without
+/// needing a full rewrite on every event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum JournalRecord {
+    Visited { url: String, timestamp: usize },
+    Domain { url: String, info: DomainInfo },
+    Redirect { from: String, to: String },
+    Purge { url: String },
+    Failed { url: String, timestamp: usize, error: String },
+}
+
+/// What a single fetch attempt produced, reported back to the scheduler in
+/// `Manager::run` so it can fold the result into the graph.
+pub enum FetchOutcome {
+    Visited {
+        url: String,
+        real_url: String,
+        info: DomainInfo,
+    },
+    Redirected {
+        url: String,
+        to: String,
+    },
+    Failed {
+        url: String,
+        error: String,
+    },
+}
+
+/// Backoff base used by `should_be_queued` for a URL with recorded failures:
+/// `base * 2^min(count, FAILURE_BACKOFF_CAP)` seconds, capped at `2^8` (~256h).
+const FAILURE_BACKOFF_BASE_SECS: usize = 60 * 60;
+const FAILURE_BACKOFF_CAP: u32 = 8;
+
 impl Manager {
     pub fn new(queue: Vec<String>) -> Self {
+        let filters = Filters::load();
+        let blocklist_patterns = filters
+            .blocklist_patterns
+            .iter()
+            .filter_map(|pattern| Regex::new(pattern).ok())
+            .collect();
+        let seeds = queue.iter().cloned().collect();
+
         let mut manager = Self {
             queue,
             graph: Graph::default(),
+            journal_len: 0,
+            filters,
+            blocklist_patterns,
+            track_changes: false,
+            seeds,
         };
 
         manager.read().ok();
@@ -18,9 +91,9 @@ impl Manager {
 
         for (host, data) in &manager.graph.domains {
             for link in &data.links {
-                let url = manager.graph.redirects.get(&link.url).unwrap_or(&link.url);
+                let url = manager.resolve_redirect(&link.url);
                 if !manager.should_be_purged(url.clone()) && manager.should_be_queued(url.clone()) {
-                    if let Ok(Ok(uri)) = url::Url::parse(host).map(|x| x.join(url)) {
+                    if let Ok(Ok(uri)) = url::Url::parse(host).map(|x| x.join(&url)) {
                         manager.queue.push(uri.to_string());
                     }
                 }
@@ -35,26 +108,194 @@ impl Manager {
     }
 
     fn read(&mut self) -> anyhow::Result<()> {
-        let text = std::fs::read_to_string("graph.json")?;
-        self.graph = serde_json::from_str(&text)?;
+        let text = std::fs::read_to_string(GRAPH_PATH).or_else(|_| std::fs::read_to_string(GRAPH_BAK_PATH));
+
+        if let Ok(text) = text {
+            self.graph = serde_json::from_str(&text)?;
+        }
+
+        self.replay_journal()
+    }
+
+    fn replay_journal(&mut self) -> anyhow::Result<()> {
+        let Ok(text) = std::fs::read_to_string(JOURNAL_PATH) else {
+            return Ok(());
+        };
+
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record: JournalRecord = serde_json::from_str(line)?;
+            self.apply_journal_record(record);
+            self.journal_len += 1;
+        }
+
         Ok(())
     }
 
-    fn write(&self) -> anyhow::Result<()> {
-        if std::fs::metadata("graph.bak.json").is_ok() {
-            std::fs::remove_file("graph.bak.json")?;
+    fn apply_journal_record(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Visited { url, timestamp } => {
+                self.graph.visited.insert(url, timestamp);
+            }
+            JournalRecord::Domain { url, info } => {
+                self.graph.domains.insert(url, info);
+            }
+            JournalRecord::Redirect { from, to } => {
+                self.graph.redirects.insert(from, to);
+            }
+            JournalRecord::Purge { url } => {
+                self.graph.domains.remove(&url);
+            }
+            JournalRecord::Failed { url, timestamp, error } => {
+                let record = self.graph.failures.entry(url).or_default();
+                record.count += 1;
+                record.last_attempt = timestamp;
+                record.last_error = error;
+            }
+        }
+    }
+
+    /// Appends `record` to the journal and, once `SNAPSHOT_INTERVAL` records
+    /// have piled up, materializes a fresh `graph.json` and truncates it.
+    fn append_journal(&mut self, record: JournalRecord) -> anyhow::Result<()> {
+        self.apply_journal_record(record.clone());
+
+        let line = serde_json::to_string(&record)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(JOURNAL_PATH)?;
+        writeln!(file, "{line}")?;
+
+        self.journal_len += 1;
+        if self.journal_len >= SNAPSHOT_INTERVAL {
+            self.snapshot()?;
         }
 
-        if std::fs::metadata("graph.json").is_ok() {
-            std::fs::rename("graph.json", "graph.bak.json")?;
+        Ok(())
+    }
+
+    /// Enables appending a unified diff of each snapshot to
+    /// `graph.changes.patch`, giving operators a human-readable audit trail
+    /// of which domains/links/redirects appeared or disappeared each cycle.
+    /// Off by default since it adds re-parsing the previous snapshot,
+    /// pretty-printing both versions and diffing them to every snapshot.
+    pub fn with_change_tracking(mut self, enabled: bool) -> Self {
+        self.track_changes = enabled;
+        self
+    }
+
+    /// Materializes the in-memory graph to `graph.json` (rotating the
+    /// previous snapshot to `graph.bak.json`) and truncates the journal,
+    /// since it is now fully represented by the snapshot. Also used to
+    /// flush state on a clean shutdown.
+    pub fn snapshot(&mut self) -> anyhow::Result<()> {
+        let previous = std::fs::read_to_string(GRAPH_PATH).ok();
+
+        if std::fs::metadata(GRAPH_BAK_PATH).is_ok() {
+            std::fs::remove_file(GRAPH_BAK_PATH)?;
+        }
+
+        if std::fs::metadata(GRAPH_PATH).is_ok() {
+            std::fs::rename(GRAPH_PATH, GRAPH_BAK_PATH)?;
         }
 
         let text = serde_json::to_string(&self.graph)?;
-        std::fs::write("graph.json", text)?;
+
+        if self.track_changes {
+            let pretty_previous = previous
+                .as_deref()
+                .and_then(|text| serde_json::from_str::<Graph>(text).ok())
+                .map(|graph| serde_json::to_string_pretty(&graph))
+                .transpose()?
+                .unwrap_or_default();
+            let pretty_new = serde_json::to_string_pretty(&self.graph)?;
+            self.record_change(&pretty_previous, &pretty_new)?;
+        }
+
+        std::fs::write(GRAPH_PATH, text)?;
+        std::fs::write(JOURNAL_PATH, "")?;
+        self.journal_len = 0;
+
+        Ok(())
+    }
+
+    /// Appends a unified diff between the previous and new snapshot to
+    /// `graph.changes.patch`, under a UTC timestamp header.
+    fn record_change(&self, previous: &str, new: &str) -> anyhow::Result<()> {
+        let patch = diffy::create_patch(previous, new);
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(CHANGES_PATH)?;
+        writeln!(file, "# {}", chrono::Utc::now().to_rfc3339())?;
+        writeln!(file, "{patch}")?;
 
         Ok(())
     }
 
+    /// Drives fetches for everything in the queue with up to `concurrency`
+    /// requests in flight at once. `run` itself acquires a semaphore permit
+    /// per in-flight request and holds it for the lifetime of that fetch, so
+    /// `fetch_fn` doesn't need to know concurrency is being bounded at all.
+    /// URLs are tracked in `in_flight` so a URL popped by one worker is never
+    /// handed to another while its fetch is still running.
+    pub async fn run<F>(&mut self, fetch_fn: F, concurrency: usize)
+    where
+        F: Fn(String) -> BoxFuture<'static, FetchOutcome> + Send + Sync + 'static,
+    {
+        let semaphore = Arc::new(Semaphore::new(concurrency));
+        let mut in_flight: HashSet<String> = HashSet::new();
+        let mut pending: Vec<BoxFuture<'static, FetchOutcome>> = Vec::new();
+
+        loop {
+            while let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                let Some(url) = self.dequeue() else {
+                    break;
+                };
+
+                if in_flight.contains(&url) {
+                    continue;
+                }
+
+                in_flight.insert(url.clone());
+                let fetch = fetch_fn(url);
+                pending.push(Box::pin(async move {
+                    let outcome = fetch.await;
+                    drop(permit);
+                    outcome
+                }));
+            }
+
+            if pending.is_empty() {
+                break;
+            }
+
+            let (outcome, _, rest) = select_all(pending).await;
+            pending = rest;
+
+            match outcome {
+                FetchOutcome::Visited { url, real_url, info } => {
+                    in_flight.remove(&url);
+                    self.mark_visited(url);
+                    self.save(real_url, info);
+                }
+                FetchOutcome::Redirected { url, to } => {
+                    in_flight.remove(&url);
+                    self.add_redirect(url, to);
+                }
+                FetchOutcome::Failed { url, error } => {
+                    in_flight.remove(&url);
+                    self.mark_failed(url, error);
+                }
+            }
+        }
+    }
+
     pub fn dequeue(&mut self) -> Option<String> {
         let len = self.queue.len();
         if len > 0 {
@@ -65,13 +306,19 @@ impl Manager {
 
     pub fn mark_visited(&mut self, url: String) {
         let timestamp = chrono::Utc::now().timestamp() as usize;
-        self.graph.visited.insert(url, timestamp);
-        self.write().ok();
+        self.append_journal(JournalRecord::Visited { url, timestamp }).ok();
+    }
+
+    pub fn mark_failed(&mut self, url: String, error: String) {
+        let timestamp = chrono::Utc::now().timestamp() as usize;
+        self.append_journal(JournalRecord::Failed { url, timestamp, error }).ok();
     }
 
     pub fn save(&mut self, real_url: String, info: DomainInfo) {
+        self.graph.failures.remove(&real_url);
+
         if self.should_be_purged(real_url.clone()) {
-            self.graph.domains.remove(&real_url);
+            self.append_journal(JournalRecord::Purge { url: real_url }).ok();
             return;
         }
 
@@ -85,25 +332,73 @@ impl Manager {
             }
         }
 
-        self.graph.domains.insert(real_url, info);
-        self.write().ok();
+        self.append_journal(JournalRecord::Domain { url: real_url, info }).ok();
         self.purge();
     }
 
     pub fn add_redirect(&mut self, from: String, to: String) {
-        self.graph.redirects.insert(from, to);
-        self.write().ok();
+        let to = self.resolve_redirect(&to);
+        self.append_journal(JournalRecord::Redirect { from, to }).ok();
+    }
+
+    /// Walks the redirect chain starting at `url` to its terminal target,
+    /// guarding against cycles. Use this instead of a single `redirects`
+    /// lookup so a chain like A -> B -> C resolves straight to C.
+    pub fn resolve_redirect(&self, url: &str) -> String {
+        let mut current = url.to_string();
+        let mut seen = HashSet::new();
+
+        while let Some(next) = self.graph.redirects.get(&current) {
+            if !seen.insert(current.clone()) {
+                break;
+            }
+            current = next.clone();
+        }
+
+        current
+    }
+
+    /// Counts inbound links per domain, resolving each link through its
+    /// redirect chain so a link to a since-moved URL still credits its
+    /// terminal target.
+    fn compute_in_degree(&self) -> HashMap<String, usize> {
+        let mut in_degree = HashMap::new();
+
+        for data in self.graph.domains.values() {
+            for link in &data.links {
+                let target = self.resolve_redirect(&link.url);
+                *in_degree.entry(target).or_insert(0usize) += 1;
+            }
+        }
+
+        in_degree
     }
 
     fn purge(&mut self) {
+        let in_degree = self.compute_in_degree();
+
         for (url, data) in self.graph.domains.clone() {
-            if self.should_be_purged(url.clone()) {
-                self.graph.domains.remove(&url);
+            let mut updated = data.clone();
+            updated.in_degree = in_degree.get(&url).copied().unwrap_or(0);
+            updated.out_degree = updated.links.len();
+
+            if updated.in_degree != data.in_degree || updated.out_degree != data.out_degree {
+                self.append_journal(JournalRecord::Domain { url: url.clone(), info: updated.clone() })
+                    .ok();
+            }
+
+            let below_min_degree = self.filters.min_in_degree > 0
+                && updated.in_degree < self.filters.min_in_degree as usize
+                && !self.seeds.contains(&url);
+
+            if self.should_be_purged(url.clone()) || below_min_degree {
+                self.append_journal(JournalRecord::Purge { url }).ok();
+                continue;
             }
 
-            for url in data.links {
-                if self.should_be_purged(url.url.clone()) {
-                    self.graph.domains.remove(&url.url);
+            for link in updated.links {
+                if self.should_be_purged(link.url.clone()) {
+                    self.append_journal(JournalRecord::Purge { url: link.url }).ok();
                 }
             }
         }
@@ -114,11 +409,17 @@ impl Manager {
                 self.queue.retain(|x| x != &entry);
             }
         }
-
-        self.write().ok();
     }
 
     fn should_be_queued(&self, url: String) -> bool {
+        if let Ok(parsed) = url::Url::parse(&url) {
+            if let Some(host) = parsed.host_str() {
+                if self.filters.allowlist_hosts.iter().any(|h| h == host) {
+                    return true;
+                }
+            }
+        }
+
         let should_refetch_empty_sites = false;
 
         if should_refetch_empty_sites
@@ -132,24 +433,33 @@ impl Manager {
             let timestamp = self.graph.visited[&url];
             let now = chrono::Utc::now().timestamp() as usize;
             let diff = now - timestamp;
-            if diff < 60 * 60 * 24 * 7 {
+            if diff < self.filters.max_refetch_age_secs as usize {
+                return false;
+            }
+        }
+
+        if let Some(record) = self.graph.failures.get(&url) {
+            let now = chrono::Utc::now().timestamp() as usize;
+            let backoff = FAILURE_BACKOFF_BASE_SECS << record.count.min(FAILURE_BACKOFF_CAP);
+            if now - record.last_attempt < backoff {
                 return false;
             }
         }
 
-        if let Some(redirect) = self.graph.redirects.get(&url) {
+        let redirect = self.resolve_redirect(&url);
+        if redirect != url {
             if should_refetch_empty_sites
-                && self.graph.domains.contains_key(redirect)
-                && self.graph.domains[redirect].links.is_empty()
+                && self.graph.domains.contains_key(&redirect)
+                && self.graph.domains[&redirect].links.is_empty()
             {
                 return true;
             }
 
-            if self.graph.visited.contains_key(redirect) {
-                let timestamp = self.graph.visited[redirect];
+            if self.graph.visited.contains_key(&redirect) {
+                let timestamp = self.graph.visited[&redirect];
                 let now = chrono::Utc::now().timestamp() as usize;
                 let diff = now - timestamp;
-                if diff < 60 * 60 * 24 * 7 {
+                if diff < self.filters.max_refetch_age_secs as usize {
                     return false;
                 }
             }
@@ -170,22 +480,150 @@ impl Manager {
             return true;
         }
 
-        if let Some(redirect) = self.graph.redirects.get(&url) {
-            if self.graph.domains.contains_key(redirect)
-                && self.graph.domains[redirect].links.len() == 1
-                && self.graph.domains[redirect].links[0].url == url
-            {
-                return true;
-            }
+        let redirect = self.resolve_redirect(&url);
+        if redirect != url
+            && self.graph.domains.contains_key(&redirect)
+            && self.graph.domains[&redirect].links.len() == 1
+            && self.graph.domains[&redirect].links[0].url == url
+        {
+            return true;
         }
 
-        // oh god stop jesus christ
-        if let Ok(url) = url::Url::parse(&url) {
-            if url.host_str() == Some("youtube.com") {
-                return true;
+        if let Ok(parsed) = url::Url::parse(&url) {
+            if let Some(host) = parsed.host_str() {
+                if self.filters.blocklist_hosts.iter().any(|h| h == host) {
+                    return true;
+                }
             }
         }
 
+        if self.blocklist_patterns.iter().any(|re| re.is_match(&url)) {
+            return true;
+        }
+
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Link;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // `Manager` reads/writes fixed paths ("graph.json" etc.) in the current
+    // directory, so these tests serialize on a lock and run inside a
+    // per-test temp directory to avoid stepping on each other.
+    static FS_LOCK: Mutex<()> = Mutex::new(());
+
+    struct TempDirGuard {
+        original: std::path::PathBuf,
+        dir: std::path::PathBuf,
+    }
+
+    impl Drop for TempDirGuard {
+        fn drop(&mut self) {
+            std::env::set_current_dir(&self.original).ok();
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    fn temp_dir(name: &str) -> TempDirGuard {
+        let dir = std::env::temp_dir().join(format!("eightyeightthirtyone_test_{}_{name}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        TempDirGuard { original, dir }
+    }
+
+    #[test]
+    fn journal_replay_reconstructs_state() {
+        let _lock = FS_LOCK.lock().unwrap();
+        let _dir = temp_dir("journal_replay");
+
+        let mut manager = Manager::new(vec![]);
+        manager
+            .append_journal(JournalRecord::Visited { url: "https://a.example/".into(), timestamp: 1000 })
+            .unwrap();
+        manager
+            .append_journal(JournalRecord::Domain {
+                url: "https://a.example/".into(),
+                info: DomainInfo {
+                    links: vec![Link { url: "https://b.example/".into() }],
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        manager
+            .append_journal(JournalRecord::Failed {
+                url: "https://c.example/".into(),
+                timestamp: 2000,
+                error: "timeout".into(),
+            })
+            .unwrap();
+
+        // Nothing was snapshotted, so a fresh `Manager` has to replay the
+        // journal against an empty (missing) graph.json to see this state.
+        let reloaded = Manager::new(vec![]);
+
+        assert_eq!(reloaded.graph.visited.get("https://a.example/"), Some(&1000));
+        assert!(reloaded.graph.domains.contains_key("https://a.example/"));
+        assert_eq!(reloaded.graph.failures.get("https://c.example/").unwrap().count, 1);
+        assert_eq!(reloaded.graph.failures["https://c.example/"].last_error, "timeout");
+    }
+
+    #[tokio::test]
+    async fn run_bounds_concurrency_and_drains_queue() {
+        let _lock = FS_LOCK.lock().unwrap();
+        let _dir = temp_dir("concurrency");
+
+        let concurrency = 2;
+        let urls: Vec<String> = (0..6).map(|i| format!("https://example.com/{i}")).collect();
+        let mut manager = Manager::new(urls);
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let in_flight_for_fetch = in_flight.clone();
+        let max_for_fetch = max_in_flight.clone();
+
+        manager
+            .run(
+                move |url| {
+                    let in_flight = in_flight_for_fetch.clone();
+                    let max_in_flight = max_for_fetch.clone();
+                    Box::pin(async move {
+                        let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(current, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                        FetchOutcome::Visited { url: url.clone(), real_url: url, info: DomainInfo::default() }
+                    })
+                },
+                concurrency,
+            )
+            .await;
+
+        assert!(max_in_flight.load(Ordering::SeqCst) <= concurrency);
+        assert!(manager.queue.is_empty());
+    }
+
+    #[test]
+    fn seed_urls_survive_min_in_degree_purge_after_dequeue() {
+        let _lock = FS_LOCK.lock().unwrap();
+        let _dir = temp_dir("seed_exemption");
+
+        std::fs::write("filters.toml", "min_in_degree = 5\n").unwrap();
+
+        let seed = "https://seed.example/".to_string();
+        let mut manager = Manager::new(vec![seed.clone()]);
+
+        assert_eq!(manager.dequeue().as_deref(), Some(seed.as_str()));
+
+        manager.save(seed.clone(), DomainInfo::default());
+
+        assert!(manager.graph.domains.contains_key(&seed));
+    }
+}
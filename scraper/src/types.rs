@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Graph {
+    pub domains: HashMap<String, DomainInfo>,
+    pub visited: HashMap<String, usize>,
+    pub redirects: HashMap<String, String>,
+    #[serde(default)]
+    pub failures: HashMap<String, FailureRecord>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DomainInfo {
+    pub links: Vec<Link>,
+    /// Inbound link count across all domains' links (a domain's links to
+    /// itself included), resolved through redirects.
+    /// Recomputed on every purge pass; used for visualization and the
+    /// `min_in_degree` pruning gate.
+    #[serde(default)]
+    pub in_degree: usize,
+    #[serde(default)]
+    pub out_degree: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Link {
+    pub url: String,
+}
+
+/// Tracks repeated fetch failures for a single URL so `Manager` can back off
+/// instead of hammering a permanently dead host.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FailureRecord {
+    pub count: u32,
+    pub last_attempt: usize,
+    pub last_error: String,
+}